@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use fancy_regex::{Captures, Regex};
+use teloxide::types::ChatId;
+
+use crate::dispatch::{Command, Ctx, Trigger};
+
+/// Per-chat user-defined variables, carried across a session so expressions
+/// like `x = 5` followed by `x * 2` work. Built-in constants (`pi`, `e`) and
+/// functions (`sin`, `sqrt`, `ln`, …) come from [`meval::Context`] itself;
+/// `log` (base 10) is added on top in [`Calc::compute`].
+type Vars = Arc<Mutex<HashMap<ChatId, HashMap<String, f64>>>>;
+
+/// Local arithmetic evaluator, exposed both as the `/calc` command and as an
+/// inline trigger for lines beginning with `=`.
+#[derive(Clone)]
+pub struct Calc {
+    vars: Vars,
+    assign: Regex,
+    trigger: Regex,
+}
+
+impl Calc {
+    pub fn new() -> Self {
+        Self {
+            vars: Vars::default(),
+            assign: Regex::new(r"^\s*([A-Za-z_][A-Za-z0-9_]*)\s*=\s*(.+)$").unwrap(),
+            trigger: Regex::new(r"^\s*=\s*(.+)$").unwrap(),
+        }
+    }
+
+    /// Evaluate `input` for `chat_id`, returning a friendly message either way.
+    fn eval(&self, chat_id: ChatId, input: &str) -> String {
+        let input = input.trim();
+        if input.is_empty() {
+            return "Usage: /calc <expression>, e.g. /calc 2*(3+4)".to_string();
+        }
+        match self.assign.captures(input) {
+            Ok(Some(caps)) => {
+                let name = caps.get(1).unwrap().as_str().to_string();
+                let expr = caps.get(2).unwrap().as_str();
+                match self.compute(chat_id, expr) {
+                    Ok(value) => {
+                        self.vars
+                            .lock()
+                            .unwrap()
+                            .entry(chat_id)
+                            .or_default()
+                            .insert(name.clone(), value);
+                        format!("{name} = {}", format_num(value))
+                    }
+                    Err(msg) => msg,
+                }
+            }
+            _ => match self.compute(chat_id, input) {
+                Ok(value) => format_num(value),
+                Err(msg) => msg,
+            },
+        }
+    }
+
+    fn compute(&self, chat_id: ChatId, expr: &str) -> Result<f64, String> {
+        let mut ctx = meval::Context::new();
+        ctx.func("log", f64::log10);
+        if let Some(vars) = self.vars.lock().unwrap().get(&chat_id) {
+            for (name, value) in vars {
+                ctx.var(name, *value);
+            }
+        }
+        let value = meval::eval_str_with_context(expr, &ctx)
+            .map_err(|e| format!("Could not evaluate `{expr}`: {e}"))?;
+        if value.is_nan() || value.is_infinite() {
+            return Err(format!("`{expr}` is undefined (division by zero?)."));
+        }
+        Ok(value)
+    }
+}
+
+impl Default for Calc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Calc {
+    fn name(&self) -> &str {
+        "calc"
+    }
+
+    fn description(&self) -> &str {
+        "Evaluate a math expression"
+    }
+
+    async fn execute(&self, ctx: &Ctx<'_>) -> Result<String> {
+        Ok(self.eval(ctx.msg.chat.id, ctx.args()))
+    }
+}
+
+#[async_trait::async_trait]
+impl Trigger for Calc {
+    fn pattern(&self) -> &Regex {
+        &self.trigger
+    }
+
+    async fn handle(&self, ctx: &Ctx<'_>, caps: &Captures<'_>) -> Result<String> {
+        let expr = caps.get(1).map_or("", |m| m.as_str());
+        Ok(self.eval(ctx.msg.chat.id, expr))
+    }
+}
+
+/// Format a result without a trailing `.0` for whole numbers.
+fn format_num(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{value}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT: ChatId = ChatId(1);
+
+    #[test]
+    fn evaluates_arithmetic_as_whole_number() {
+        let calc = Calc::new();
+        assert_eq!(calc.eval(CHAT, "2*(3+4)"), "14");
+    }
+
+    #[test]
+    fn assignment_reports_and_carries_over() {
+        let calc = Calc::new();
+        assert_eq!(calc.eval(CHAT, "x = 5"), "x = 5");
+        assert_eq!(calc.eval(CHAT, "x * 2"), "10");
+    }
+
+    #[test]
+    fn variables_are_per_chat() {
+        let calc = Calc::new();
+        calc.eval(CHAT, "x = 5");
+        assert!(calc.eval(ChatId(2), "x").starts_with("Could not evaluate"));
+    }
+
+    #[test]
+    fn supports_constants_and_functions() {
+        let calc = Calc::new();
+        assert_eq!(calc.eval(CHAT, "sqrt(16)"), "4");
+        assert_eq!(calc.eval(CHAT, "sin(0)"), "0");
+        assert!(calc.eval(CHAT, "pi").starts_with("3.14"));
+    }
+
+    #[test]
+    fn division_by_zero_is_friendly() {
+        let calc = Calc::new();
+        assert!(calc.eval(CHAT, "1/0").contains("undefined"));
+    }
+
+    #[test]
+    fn unknown_identifier_is_friendly() {
+        let calc = Calc::new();
+        assert!(calc.eval(CHAT, "nope + 1").starts_with("Could not evaluate"));
+    }
+
+    #[test]
+    fn empty_input_shows_usage() {
+        let calc = Calc::new();
+        assert!(calc.eval(CHAT, "   ").starts_with("Usage:"));
+    }
+
+    #[test]
+    fn format_num_keeps_fractions() {
+        assert_eq!(format_num(2.5), "2.5");
+        assert_eq!(format_num(4.0), "4");
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use fancy_regex::{Captures, Regex};
+use teloxide::prelude::*;
+
+use crate::State;
+
+/// Everything a handler needs to react to a message: the bot handle, the
+/// triggering message, the resolved author username and the shared [`State`].
+pub struct Ctx<'a> {
+    pub bot: &'a Bot,
+    pub msg: &'a Message,
+    pub username: String,
+    pub state: Arc<State>,
+}
+
+impl Ctx<'_> {
+    /// The full message text, or an empty string for non-text messages.
+    pub fn text(&self) -> &str {
+        self.msg.text().unwrap_or_default()
+    }
+
+    /// The argument portion of a prefix command, i.e. everything after the
+    /// first whitespace-separated token.
+    pub fn args(&self) -> &str {
+        command_args(self.text())
+    }
+}
+
+/// Split a command line into its argument portion: everything after the first
+/// whitespace-separated token, trimmed.
+pub fn command_args(text: &str) -> &str {
+    text.splitn(2, char::is_whitespace)
+        .nth(1)
+        .unwrap_or_default()
+        .trim()
+}
+
+/// A prefix command invoked as `/<name>`.
+#[async_trait::async_trait]
+pub trait Command: Send + Sync {
+    /// The name the command registers under (without the leading slash).
+    fn name(&self) -> &str;
+    /// Short description shown in Telegram's command menu.
+    fn description(&self) -> &str;
+    /// Run the command and return the text to send back to the chat.
+    async fn execute(&self, ctx: &Ctx<'_>) -> Result<String>;
+}
+
+/// A handler fired when its compiled pattern matches the message text.
+#[async_trait::async_trait]
+pub trait Trigger: Send + Sync {
+    /// The pattern tested against incoming message text.
+    fn pattern(&self) -> &Regex;
+    /// React to a match, with the capture groups from [`Trigger::pattern`].
+    async fn handle(&self, ctx: &Ctx<'_>, caps: &Captures<'_>) -> Result<String>;
+}
+
+/// Registry of pluggable commands and triggers, consulted by `handle_msg`.
+#[derive(Default)]
+pub struct Registry {
+    commands: HashMap<String, Box<dyn Command>>,
+    triggers: Vec<Box<dyn Trigger>>,
+}
+
+impl Registry {
+    pub fn register_command<C: Command + 'static>(&mut self, command: C) {
+        self.commands.insert(command.name().to_string(), Box::new(command));
+    }
+
+    pub fn register_trigger<T: Trigger + 'static>(&mut self, trigger: T) {
+        self.triggers.push(Box::new(trigger));
+    }
+
+    pub fn command(&self, name: &str) -> Option<&dyn Command> {
+        self.commands.get(name).map(|c| c.as_ref())
+    }
+
+    pub fn triggers(&self) -> &[Box<dyn Trigger>] {
+        &self.triggers
+    }
+
+    /// The registered commands as Telegram [`BotCommand`]s, for
+    /// `set_my_commands`. Sorted by name so the menu order is stable.
+    pub fn bot_commands(&self) -> Vec<teloxide::types::BotCommand> {
+        let mut commands: Vec<_> = self
+            .commands
+            .values()
+            .map(|c| teloxide::types::BotCommand::new(c.name(), c.description()))
+            .collect();
+        commands.sort_by(|a, b| a.command.cmp(&b.command));
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl Command for Echo {
+        fn name(&self) -> &str {
+            "echo"
+        }
+        fn description(&self) -> &str {
+            "echo"
+        }
+        async fn execute(&self, _ctx: &Ctx<'_>) -> Result<String> {
+            Ok(String::new())
+        }
+    }
+
+    #[test]
+    fn command_args_splits_on_first_whitespace() {
+        assert_eq!(command_args("/calc 2 * 3"), "2 * 3");
+        assert_eq!(command_args("/model"), "");
+        assert_eq!(command_args("/model   llama3  "), "llama3");
+        assert_eq!(command_args(""), "");
+    }
+
+    #[test]
+    fn registry_resolves_registered_command_by_name() {
+        let mut registry = Registry::default();
+        registry.register_command(Echo);
+        assert!(registry.command("echo").is_some());
+        assert!(registry.command("missing").is_none());
+    }
+}
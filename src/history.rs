@@ -1,32 +1,322 @@
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
 
-use ollama_rs::generation::chat::ChatMessage;
+use anyhow::Result;
+use ollama_rs::generation::chat::{ChatMessage, MessageRole};
 use teloxide::types::ChatId;
+use tokio::sync::RwLock;
 
-type Messages = std::sync::Arc<std::sync::Mutex<Vec<ChatMessage>>>;
+type Messages = Arc<Mutex<Vec<ChatMessage>>>;
 type MessagesHashMap = HashMap<ChatId, Messages>;
 
+/// Default number of turns fed back into the model when no explicit limit is
+/// requested. Keeps prompts bounded regardless of how long a chat has run.
+pub const DEFAULT_LIMIT: usize = 20;
+
+fn role_to_str(role: &MessageRole) -> &'static str {
+    match role {
+        MessageRole::User => "user",
+        MessageRole::Assistant => "assistant",
+        MessageRole::System => "system",
+        MessageRole::Tool => "tool",
+    }
+}
+
+fn role_from_str(role: &str) -> MessageRole {
+    match role {
+        "assistant" => MessageRole::Assistant,
+        "system" => MessageRole::System,
+        "tool" => MessageRole::Tool,
+        _ => MessageRole::User,
+    }
+}
+
+/// Backing store for chat messages. The in-memory implementation keeps data
+/// only for the process lifetime; the SQLite one survives restarts.
+#[async_trait::async_trait]
+pub trait MessageStore: Send + Sync {
+    async fn append(&self, chat_id: ChatId, message: &ChatMessage) -> Result<()>;
+    async fn recent(&self, chat_id: ChatId, limit: usize) -> Result<Vec<ChatMessage>>;
+    async fn clear(&self, chat_id: ChatId) -> Result<()>;
+    /// Persist the active model chosen for `chat_id`.
+    async fn set_model(&self, chat_id: ChatId, model: &str) -> Result<()>;
+    /// Return the model chosen for `chat_id`, if any.
+    async fn get_model(&self, chat_id: ChatId) -> Result<Option<String>>;
+}
+
+/// Volatile store, used when no database is configured.
 #[derive(Default)]
+pub struct MemoryStore {
+    messages: RwLock<HashMap<ChatId, Vec<ChatMessage>>>,
+    models: RwLock<HashMap<ChatId, String>>,
+}
+
+#[async_trait::async_trait]
+impl MessageStore for MemoryStore {
+    async fn append(&self, chat_id: ChatId, message: &ChatMessage) -> Result<()> {
+        self.messages
+            .write()
+            .await
+            .entry(chat_id)
+            .or_default()
+            .push(message.clone());
+        Ok(())
+    }
+
+    async fn recent(&self, chat_id: ChatId, limit: usize) -> Result<Vec<ChatMessage>> {
+        let guard = self.messages.read().await;
+        Ok(guard.get(&chat_id).map_or_else(Vec::new, |messages| {
+            let start = messages.len().saturating_sub(limit);
+            messages[start..].to_vec()
+        }))
+    }
+
+    async fn clear(&self, chat_id: ChatId) -> Result<()> {
+        self.messages.write().await.remove(&chat_id);
+        Ok(())
+    }
+
+    async fn set_model(&self, chat_id: ChatId, model: &str) -> Result<()> {
+        self.models.write().await.insert(chat_id, model.to_string());
+        Ok(())
+    }
+
+    async fn get_model(&self, chat_id: ChatId) -> Result<Option<String>> {
+        Ok(self.models.read().await.get(&chat_id).cloned())
+    }
+}
+
+/// SQLite-backed store persisting every turn to a `messages` table.
+pub struct SqliteStore {
+    pool: sqlx::SqlitePool,
+}
+
+impl SqliteStore {
+    /// Connect to `url` (e.g. `sqlite://history.db?mode=rwc`) and ensure the
+    /// schema exists.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let pool = sqlx::SqlitePool::connect(url).await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS messages (
+                 id       INTEGER PRIMARY KEY AUTOINCREMENT,
+                 chat_id  INTEGER NOT NULL,
+                 role     TEXT NOT NULL,
+                 content  TEXT NOT NULL,
+                 ts       INTEGER NOT NULL
+             )",
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_messages_chat ON messages(chat_id, id)")
+            .execute(&pool)
+            .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS chat_model (
+                 chat_id INTEGER PRIMARY KEY,
+                 model   TEXT NOT NULL
+             )",
+        )
+        .execute(&pool)
+        .await?;
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageStore for SqliteStore {
+    async fn append(&self, chat_id: ChatId, message: &ChatMessage) -> Result<()> {
+        let ts = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        sqlx::query("INSERT INTO messages (chat_id, role, content, ts) VALUES (?, ?, ?, ?)")
+            .bind(chat_id.0)
+            .bind(role_to_str(&message.role))
+            .bind(&message.content)
+            .bind(ts)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn recent(&self, chat_id: ChatId, limit: usize) -> Result<Vec<ChatMessage>> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "SELECT role, content FROM messages
+             WHERE chat_id = ? ORDER BY id DESC LIMIT ?",
+        )
+        .bind(chat_id.0)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .rev()
+            .map(|(role, content)| ChatMessage::new(role_from_str(&role), content))
+            .collect())
+    }
+
+    async fn clear(&self, chat_id: ChatId) -> Result<()> {
+        sqlx::query("DELETE FROM messages WHERE chat_id = ?")
+            .bind(chat_id.0)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_model(&self, chat_id: ChatId, model: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO chat_model (chat_id, model) VALUES (?, ?)
+             ON CONFLICT(chat_id) DO UPDATE SET model = excluded.model",
+        )
+        .bind(chat_id.0)
+        .bind(model)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_model(&self, chat_id: ChatId) -> Result<Option<String>> {
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT model FROM chat_model WHERE chat_id = ?")
+                .bind(chat_id.0)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(row.map(|(model,)| model))
+    }
+}
+
+/// Durable conversation history. The `Arc<Mutex<Vec<ChatMessage>>>` handed out
+/// by [`History::get`] doubles as a write-through cache that `ollama_rs` mutates
+/// while streaming; completed turns are flushed to [`MessageStore`] afterwards.
 pub struct History {
-    messages: tokio::sync::RwLock<MessagesHashMap>,
+    store: Box<dyn MessageStore>,
+    cache: RwLock<MessagesHashMap>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            store: Box::new(MemoryStore::default()),
+            cache: RwLock::new(MessagesHashMap::new()),
+        }
+    }
 }
 
 impl History {
-    pub async fn get<'a>(&'a self, chat_id: ChatId) -> ChatHistory<'a> {
-        self.messages.write().await.entry(chat_id).or_default();
-        let guard = self.messages.read().await;
-        let messages = guard.get(&chat_id).unwrap().clone();
-        ChatHistory { guard, messages }
+    /// Build a history layer from the environment: when `HISTORY_DATABASE_URL`
+    /// is set a SQLite store is used, otherwise an in-memory one.
+    pub async fn from_env() -> Result<Self> {
+        let store: Box<dyn MessageStore> = match std::env::var("HISTORY_DATABASE_URL") {
+            Ok(url) => Box::new(SqliteStore::connect(&url).await?),
+            Err(_) => Box::new(MemoryStore::default()),
+        };
+        Ok(Self {
+            store,
+            cache: RwLock::new(MessagesHashMap::new()),
+        })
+    }
+
+    /// Return the most recent `limit` turns for `chat_id`, rehydrated from the
+    /// store into the per-chat cache.
+    pub async fn get(&self, chat_id: ChatId, limit: usize) -> Result<ChatHistory> {
+        let recent = self.store.recent(chat_id, limit).await?;
+        // Hand out a fresh vec per call rather than a shared cache entry:
+        // `ollama_rs` mutates it in place while streaming, and with per-chat
+        // serialization disabled a concurrent `get` for the same chat would
+        // otherwise overwrite a still-appending vec and tear the history.
+        let messages = Arc::new(Mutex::new(recent));
+        self.cache.write().await.insert(chat_id, messages.clone());
+        Ok(ChatHistory { messages })
+    }
+
+    /// Persist a single completed message to the store. The cache is not
+    /// touched.
+    pub async fn append(&self, chat_id: ChatId, message: ChatMessage) -> Result<()> {
+        self.store.append(chat_id, &message).await
+    }
+
+    /// Set the active model for `chat_id`, persisting it alongside history.
+    pub async fn set_model(&self, chat_id: ChatId, model: &str) -> Result<()> {
+        self.store.set_model(chat_id, model).await
+    }
+
+    /// Return the model chosen for `chat_id`, if one has been set.
+    pub async fn get_model(&self, chat_id: ChatId) -> Result<Option<String>> {
+        self.store.get_model(chat_id).await
     }
 
-    pub async fn clear(&self, chat_id: ChatId) {
-        if let Some(messages) = self.messages.read().await.get(&chat_id) {
+    pub async fn clear(&self, chat_id: ChatId) -> Result<()> {
+        if let Some(messages) = self.cache.read().await.get(&chat_id) {
             messages.lock().unwrap().clear();
         }
+        self.store.clear(chat_id).await
     }
 }
 
-pub struct ChatHistory<'a> {
-    guard: tokio::sync::RwLockReadGuard<'a, MessagesHashMap>,
+pub struct ChatHistory {
     pub messages: Messages,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn recent_preserves_append_order() {
+        let store = MemoryStore::default();
+        let chat = ChatId(1);
+        store.append(chat, &ChatMessage::user("one")).await.unwrap();
+        store
+            .append(chat, &ChatMessage::assistant("two"))
+            .await
+            .unwrap();
+        store.append(chat, &ChatMessage::user("three")).await.unwrap();
+
+        let recent = store.recent(chat, 10).await.unwrap();
+        let contents: Vec<_> = recent.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["one", "two", "three"]);
+    }
+
+    #[tokio::test]
+    async fn recent_truncates_to_limit() {
+        let store = MemoryStore::default();
+        let chat = ChatId(1);
+        for i in 0..5 {
+            store
+                .append(chat, &ChatMessage::user(i.to_string()))
+                .await
+                .unwrap();
+        }
+
+        let recent = store.recent(chat, 2).await.unwrap();
+        let contents: Vec<_> = recent.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["3", "4"]);
+    }
+
+    #[tokio::test]
+    async fn model_round_trips_per_chat() {
+        let store = MemoryStore::default();
+        assert_eq!(store.get_model(ChatId(1)).await.unwrap(), None);
+
+        store.set_model(ChatId(1), "llama3").await.unwrap();
+        store.set_model(ChatId(1), "qwen").await.unwrap();
+        assert_eq!(
+            store.get_model(ChatId(1)).await.unwrap().as_deref(),
+            Some("qwen")
+        );
+        assert_eq!(store.get_model(ChatId(2)).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn clear_drops_only_its_chat() {
+        let store = MemoryStore::default();
+        store.append(ChatId(1), &ChatMessage::user("a")).await.unwrap();
+        store.append(ChatId(2), &ChatMessage::user("b")).await.unwrap();
+
+        store.clear(ChatId(1)).await.unwrap();
+
+        assert!(store.recent(ChatId(1), 10).await.unwrap().is_empty());
+        assert_eq!(store.recent(ChatId(2), 10).await.unwrap().len(), 1);
+    }
+}
@@ -3,7 +3,9 @@ use ollama_rs::{
     Ollama,
     generation::chat::{ChatMessage, request::ChatMessageRequest},
 };
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use teloxide::{
     dispatching::{HandlerExt, UpdateFilterExt},
     macros,
@@ -14,22 +16,95 @@ use teloxide::{
 };
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio_stream::StreamExt;
-use tokio_util::{bytes, io::StreamReader};
+use tokio_util::{bytes, io::StreamReader, sync::CancellationToken};
 
+mod calc;
+mod dispatch;
 mod history;
+mod markdownv2;
+mod model;
 mod parser;
+mod stop;
 mod util;
 
+use dispatch::{Ctx, Registry};
 use history::History;
 use parser::MessageParser;
 use util::truncate_str;
 
 const MODEL_NAME: &str = "qwen2.5-coder:32b";
 
-#[derive(Default)]
 struct State {
     ollama: Ollama,
     history: History,
+    registry: Registry,
+    /// In-flight generations, keyed by chat. The `u64` is a monotonically
+    /// increasing id identifying a particular generation, since
+    /// [`CancellationToken`] is not `PartialEq`.
+    generations: tokio::sync::Mutex<HashMap<ChatId, (u64, CancellationToken)>>,
+    /// Source of the per-generation ids.
+    next_gen_id: AtomicU64,
+}
+
+impl State {
+    async fn from_env() -> Result<Self> {
+        Ok(Self {
+            ollama: Ollama::default(),
+            history: History::from_env().await?,
+            registry: build_registry(),
+            generations: tokio::sync::Mutex::new(HashMap::new()),
+            next_gen_id: AtomicU64::new(0),
+        })
+    }
+
+    /// Begin a generation for `chat_id`, cancelling any prior one still running,
+    /// and return the id and token the new stream should watch.
+    async fn begin_generation(&self, chat_id: ChatId) -> (u64, CancellationToken) {
+        let id = self.next_gen_id.fetch_add(1, Ordering::Relaxed);
+        let token = CancellationToken::new();
+        if let Some((_, previous)) = self
+            .generations
+            .lock()
+            .await
+            .insert(chat_id, (id, token.clone()))
+        {
+            previous.cancel();
+        }
+        (id, token)
+    }
+
+    /// Cancel the in-flight generation for `chat_id`, if any.
+    async fn cancel_generation(&self, chat_id: ChatId) -> bool {
+        match self.generations.lock().await.remove(&chat_id) {
+            Some((_, token)) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drop the tracked generation once it finishes, unless it has already been
+    /// superseded by a newer one.
+    async fn finish_generation(&self, chat_id: ChatId, id: u64) {
+        let mut generations = self.generations.lock().await;
+        if generations.get(&chat_id).is_some_and(|(cur, _)| *cur == id) {
+            generations.remove(&chat_id);
+        }
+    }
+}
+
+/// Assemble the command/trigger registry. New behaviors plug in here without
+/// touching the dptree wiring.
+fn build_registry() -> Registry {
+    let mut registry = Registry::default();
+    let calc = calc::Calc::new();
+    registry.register_trigger(calc.clone());
+    registry.register_command(calc);
+    registry.register_command(model::ModelCommand);
+    registry.register_command(stop::StopCommand::new("stop"));
+    registry.register_command(stop::StopCommand::new("s"));
+    registry
 }
 
 /// These commands are supported:
@@ -49,7 +124,12 @@ async fn main() -> Result<()> {
     env_logger::init();
     log::info!("Starting the bot...");
     let bot = Bot::from_env();
-    bot.set_my_commands(Command::bot_commands()).await?;
+    let state = Arc::new(State::from_env().await?);
+    // The registry is the source of truth for the pluggable commands, so merge
+    // their names into the menu alongside the built-in help/clear.
+    let mut commands = Command::bot_commands();
+    commands.extend(state.registry.bot_commands());
+    bot.set_my_commands(commands).await?;
     log::info!("Finished setting up the bot...");
     Dispatcher::builder(
         bot,
@@ -62,11 +142,17 @@ async fn main() -> Result<()> {
             )
             .branch(
                 dptree::filter(|msg: Message| msg.text().is_some_and(|x| x.starts_with("/")))
-                    .endpoint(handle_uknown_command),
+                    .endpoint(handle_command),
             )
             .endpoint(handle_msg),
     )
-    .dependencies(dptree::deps![Arc::new(State::default())])
+    // Opt out of teloxide's default per-chat serialization: otherwise a `/stop`
+    // or a follow-up message is queued behind the running `handle_msg` and only
+    // dispatched once generation finishes, by which point there is nothing left
+    // to cancel. With updates dispatched concurrently, a fresh message can call
+    // `begin_generation` to cancel the prior stream while it is still running.
+    .distribution_function(|_| None::<std::convert::Infallible>)
+    .dependencies(dptree::deps![state])
     .enable_ctrlc_handler()
     .build()
     .dispatch()
@@ -87,21 +173,32 @@ async fn handle_help(bot: Bot, msg: Message) -> Result<()> {
 }
 
 async fn handle_clear(bot: Bot, msg: Message, state: Arc<State>) -> Result<()> {
-    state.history.clear(msg.chat.id).await;
+    state.history.clear(msg.chat.id).await?;
     let usr = message_username(&msg);
     log::debug!("Clear history for user <{usr}>.");
     bot.send_message(msg.chat.id, "Context cleared.").await?;
     Ok(())
 }
 
-async fn handle_uknown_command(bot: Bot, msg: Message) -> Result<()> {
-    let text = msg
-        .text()
-        .unwrap_or_default()
+async fn handle_command(bot: Bot, msg: Message, state: Arc<State>) -> Result<()> {
+    let text = msg.text().unwrap_or_default();
+    let name = text
         .split_whitespace()
         .next()
-        .unwrap_or_default();
-    let cmd = truncate_str(text, 50);
+        .unwrap_or_default()
+        .trim_start_matches('/');
+    if let Some(command) = state.registry.command(name) {
+        let ctx = Ctx {
+            bot: &bot,
+            msg: &msg,
+            username: message_username(&msg),
+            state: state.clone(),
+        };
+        let reply = command.execute(&ctx).await?;
+        handle_complete_state(&bot, msg.chat.id, &mut None, &reply).await?;
+        return Ok(());
+    }
+    let cmd = truncate_str(text.split_whitespace().next().unwrap_or_default(), 50);
     bot.send_message(
         msg.chat.id,
         format!("Unknown command: {cmd}. Use /help to see available commands."),
@@ -111,25 +208,38 @@ async fn handle_uknown_command(bot: Bot, msg: Message) -> Result<()> {
 }
 
 pub fn sanitize_text(s: &str) -> String {
-    [
-        "<p>", "</p>", "<br />", "<li>", "</li>", "<ol>", "</ol>", "<h1>", "</h1>", "<h2>",
-        "</h2>", "<h3>", "</h3>", "<h4>", "</h4>", "<h5>", "</h5>", "<ul>", "</ul>",
-    ]
-    .iter()
-    .fold(markdown::to_html(s), |s, pattern| s.replace(pattern, ""))
+    markdownv2::to_markdown_v2(s)
 }
 
 async fn handle_msg(bot: Bot, msg: Message, state: Arc<State>) -> Result<()> {
     if let Some(text) = msg.text() {
         let usr = message_username(&msg);
         log::debug!("User <{usr}> send request: {}.", truncate_str(text, 20));
-        let chat_history = state.history.get(msg.chat.id).await;
+        for trigger in state.registry.triggers() {
+            if let Some(caps) = trigger.pattern().captures(text)? {
+                let ctx = Ctx {
+                    bot: &bot,
+                    msg: &msg,
+                    username: usr.clone(),
+                    state: state.clone(),
+                };
+                let reply = trigger.handle(&ctx, &caps).await?;
+                handle_complete_state(&bot, msg.chat.id, &mut None, &reply).await?;
+                return Ok(());
+            }
+        }
+        let chat_history = state.history.get(msg.chat.id, history::DEFAULT_LIMIT).await?;
+        let model = state
+            .history
+            .get_model(msg.chat.id)
+            .await?
+            .unwrap_or_else(|| MODEL_NAME.to_string());
         let stream = state
             .ollama
             .send_chat_messages_with_history_stream(
                 chat_history.messages,
                 ChatMessageRequest::new(
-                    MODEL_NAME.to_string(),
+                    model,
                     vec![ChatMessage::user(text.to_string())],
                 ),
             )
@@ -138,20 +248,55 @@ async fn handle_msg(bot: Bot, msg: Message, state: Arc<State>) -> Result<()> {
                 resp.map(|resp| bytes::Bytes::from(resp.message.content.as_bytes().to_owned()))
                     .map_err(|_| std::io::Error::other(anyhow!("")))
             });
+        let (gen_id, cancel) = state.begin_generation(msg.chat.id).await;
         let mut parser = MessageParser::new(BufReader::new(StreamReader::new(stream)).lines());
         let mut msg_id = None;
-        while let Some(state) = {
+        let mut answer = String::new();
+        let mut last_text = String::new();
+        let mut stopped = false;
+        loop {
             bot.send_chat_action(msg.chat.id, ChatAction::Typing)
                 .await?;
-            parser.next_state().await?
-        } {
-            log::debug!("User <{usr}> response: {state:?}");
-            if state.is_complete {
-                handle_complete_state(&bot, msg.chat.id, &mut msg_id, &state.text).await?;
+            let parse_state = tokio::select! {
+                biased;
+                _ = cancel.cancelled() => {
+                    stopped = true;
+                    break;
+                }
+                next = parser.next_state() => match next? {
+                    Some(parse_state) => parse_state,
+                    None => break,
+                },
+            };
+            log::debug!("User <{usr}> response: {parse_state:?}");
+            if parse_state.is_complete {
+                answer.push_str(&parse_state.text);
+                last_text = parse_state.text.clone();
+                handle_complete_state(&bot, msg.chat.id, &mut msg_id, &parse_state.text).await?;
             } else {
-                handle_incomplete_state(&bot, msg.chat.id, &mut msg_id, &state.buffer).await?;
+                last_text = parse_state.buffer.clone();
+                handle_incomplete_state(&bot, msg.chat.id, &mut msg_id, &parse_state.buffer)
+                    .await?;
             }
         }
+        state.finish_generation(msg.chat.id, gen_id).await;
+        if stopped {
+            finalize_stopped(&bot, msg.chat.id, &mut msg_id, &last_text).await?;
+        }
+        state
+            .history
+            .append(msg.chat.id, ChatMessage::user(text.to_string()))
+            .await?;
+        // Persist the full answer on clean completion, or the finalized partial
+        // when stopped; never an empty assistant turn, which would poison the
+        // context fed back to the model.
+        let assistant_text = if stopped { last_text } else { answer };
+        if !assistant_text.is_empty() {
+            state
+                .history
+                .append(msg.chat.id, ChatMessage::assistant(assistant_text))
+                .await?;
+        }
     }
     Ok(())
 }
@@ -164,16 +309,34 @@ async fn handle_complete_state(
 ) -> Result<()> {
     if let Some(id) = msg_id.take() {
         bot.edit_message_text(chat_id, id, sanitize_text(text))
-            .parse_mode(ParseMode::Html)
+            .parse_mode(ParseMode::MarkdownV2)
             .await?;
     } else {
         bot.send_message(chat_id, sanitize_text(text))
-            .parse_mode(ParseMode::Html)
+            .parse_mode(ParseMode::MarkdownV2)
             .await?;
     }
     Ok(())
 }
 
+/// Edit the last streamed message to mark a generation as stopped by the user.
+async fn finalize_stopped(
+    bot: &Bot,
+    chat_id: ChatId,
+    msg_id: &mut Option<MessageId>,
+    text: &str,
+) -> Result<()> {
+    // Send as plain text: a stream cut mid-entity yields unbalanced Markdown
+    // that MarkdownV2 would reject, dropping the stop confirmation entirely.
+    let body = format!("{text}\n\n⏹ stopped");
+    if let Some(id) = msg_id.take() {
+        bot.edit_message_text(chat_id, id, body).await?;
+    } else {
+        bot.send_message(chat_id, body).await?;
+    }
+    Ok(())
+}
+
 async fn handle_incomplete_state(
     bot: &Bot,
     chat_id: ChatId,
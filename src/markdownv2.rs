@@ -0,0 +1,154 @@
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag};
+
+/// Characters Telegram requires escaping in MarkdownV2 normal text.
+const RESERVED: &[char] = &[
+    '_', '*', '[', ']', '(', ')', '~', '`', '>', '#', '+', '-', '=', '|', '{', '}', '.', '!', '\\',
+];
+
+fn escape_text(s: &str, out: &mut String) {
+    for c in s.chars() {
+        if RESERVED.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+/// Inside code entities only `` ` `` and `\` are special.
+fn escape_code(s: &str, out: &mut String) {
+    for c in s.chars() {
+        if c == '`' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+/// Render model Markdown into Telegram MarkdownV2, escaping reserved
+/// characters in normal text while leaving code verbatim.
+pub fn to_markdown_v2(src: &str) -> String {
+    let mut out = String::new();
+    // Active ordered-list counters; `None` marks a bulleted list.
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut in_code_block = false;
+
+    for event in Parser::new(src) {
+        match event {
+            Event::Start(Tag::Paragraph) => {}
+            Event::End(Tag::Paragraph) => out.push_str("\n\n"),
+            Event::Start(Tag::Heading(..)) => out.push('*'),
+            Event::End(Tag::Heading(..)) => out.push_str("*\n\n"),
+            Event::Start(Tag::Strong) | Event::End(Tag::Strong) => out.push('*'),
+            Event::Start(Tag::Emphasis) | Event::End(Tag::Emphasis) => out.push('_'),
+            Event::Start(Tag::Strikethrough) | Event::End(Tag::Strikethrough) => out.push('~'),
+            Event::Start(Tag::CodeBlock(kind)) => {
+                in_code_block = true;
+                out.push_str("```");
+                if let CodeBlockKind::Fenced(lang) = kind {
+                    out.push_str(lang.trim());
+                }
+                out.push('\n');
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                in_code_block = false;
+                out.push_str("```\n\n");
+            }
+            Event::Start(Tag::List(start)) => lists.push(start),
+            Event::End(Tag::List(_)) => {
+                lists.pop();
+                out.push('\n');
+            }
+            Event::Start(Tag::Item) => {
+                let indent = "  ".repeat(lists.len().saturating_sub(1));
+                out.push_str(&indent);
+                match lists.last_mut() {
+                    Some(Some(n)) => {
+                        out.push_str(&format!("{n}\\. "));
+                        *n += 1;
+                    }
+                    _ => out.push_str("\\- "),
+                }
+            }
+            Event::End(Tag::Item) => out.push('\n'),
+            Event::Start(Tag::Link(..)) => out.push('['),
+            Event::End(Tag::Link(_, url, _)) => {
+                out.push_str("](");
+                escape_url(&url, &mut out);
+                out.push(')');
+            }
+            Event::Code(text) => {
+                out.push('`');
+                escape_code(&text, &mut out);
+                out.push('`');
+            }
+            Event::Text(text) => {
+                if in_code_block {
+                    escape_code(&text, &mut out);
+                } else {
+                    escape_text(&text, &mut out);
+                }
+            }
+            Event::SoftBreak => out.push('\n'),
+            Event::HardBreak => out.push('\n'),
+            Event::Rule => out.push_str("\n\\-\\-\\-\n\n"),
+            _ => {}
+        }
+    }
+
+    out.trim_end().to_string()
+}
+
+/// Inside a link destination only `)` and `\` need escaping.
+fn escape_url(url: &str, out: &mut String) {
+    for c in url.chars() {
+        if c == ')' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_reserved_characters_in_text() {
+        assert_eq!(to_markdown_v2("a.b!c-d"), "a\\.b\\!c\\-d");
+        assert_eq!(to_markdown_v2("1 + 1 = 2"), "1 \\+ 1 \\= 2");
+    }
+
+    #[test]
+    fn inline_code_is_verbatim_except_backtick() {
+        assert_eq!(to_markdown_v2("`x_y.z`"), "`x_y.z`");
+    }
+
+    #[test]
+    fn fenced_code_block_is_left_verbatim() {
+        let src = "```rust\nlet x = 1.0;\n```";
+        assert_eq!(to_markdown_v2(src), "```rust\nlet x = 1.0;\n```");
+    }
+
+    #[test]
+    fn nested_bold_and_italic() {
+        assert_eq!(to_markdown_v2("**bold _italic_**"), "*bold _italic_*");
+    }
+
+    #[test]
+    fn bulleted_list() {
+        assert_eq!(to_markdown_v2("- a\n- b"), "\\- a\n\\- b");
+    }
+
+    #[test]
+    fn ordered_list_numbers_items() {
+        assert_eq!(to_markdown_v2("1. a\n2. b"), "1\\. a\n2\\. b");
+    }
+
+    #[test]
+    fn link_maps_to_markdownv2() {
+        assert_eq!(
+            to_markdown_v2("[text](http://example.com)"),
+            "[text](http://example.com)"
+        );
+    }
+}
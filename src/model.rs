@@ -0,0 +1,52 @@
+use anyhow::Result;
+
+use crate::dispatch::{Command, Ctx};
+
+/// `/model` lists the models available on the Ollama server, or switches the
+/// active model for the current chat when given a name.
+pub struct ModelCommand;
+
+#[async_trait::async_trait]
+impl Command for ModelCommand {
+    fn name(&self) -> &str {
+        "model"
+    }
+
+    fn description(&self) -> &str {
+        "List or set the chat's model"
+    }
+
+    async fn execute(&self, ctx: &Ctx<'_>) -> Result<String> {
+        let chat_id = ctx.msg.chat.id;
+        let arg = ctx.args();
+        let models = ctx.state.ollama.list_local_models().await?;
+        if arg.is_empty() {
+            let current = ctx
+                .state
+                .history
+                .get_model(chat_id)
+                .await?
+                .unwrap_or_else(|| crate::MODEL_NAME.to_string());
+            let list = models
+                .iter()
+                .map(|m| {
+                    if m.name == current {
+                        format!("• {} (active)", m.name)
+                    } else {
+                        format!("• {}", m.name)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            return Ok(format!("Available models:\n{list}"));
+        }
+        if models.iter().any(|m| m.name == arg) {
+            ctx.state.history.set_model(chat_id, arg).await?;
+            Ok(format!("Model set to {arg}."))
+        } else {
+            Ok(format!(
+                "Unknown model: {arg}. Use /model to list available models."
+            ))
+        }
+    }
+}
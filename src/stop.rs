@@ -0,0 +1,34 @@
+use anyhow::Result;
+
+use crate::dispatch::{Command, Ctx};
+
+/// `/stop` (alias `/s`) aborts the in-flight generation for the chat. The
+/// running stream observes the cancellation and finalizes its partial message.
+pub struct StopCommand {
+    name: &'static str,
+}
+
+impl StopCommand {
+    pub fn new(name: &'static str) -> Self {
+        Self { name }
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for StopCommand {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn description(&self) -> &str {
+        "Stop the current generation"
+    }
+
+    async fn execute(&self, ctx: &Ctx<'_>) -> Result<String> {
+        if ctx.state.cancel_generation(ctx.msg.chat.id).await {
+            Ok("Stopping…".to_string())
+        } else {
+            Ok("Nothing is generating right now.".to_string())
+        }
+    }
+}